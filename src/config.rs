@@ -1,3 +1,5 @@
+use crate::error::Error;
+use bollard::Docker;
 use serde::Deserialize;
 use std::env;
 use std::path::PathBuf;
@@ -21,20 +23,46 @@ pub struct Config {
     state_directory: PathBuf,
     #[serde(default)]
     connect_mode: DockerConnectMode,
+    /// How many containers may have an update check/action in flight at once
+    ///
+    /// Defaults to the number of available CPUs, so a slow build or pull for one game doesn't
+    /// stall the update checks for every other container until the next tick.
+    #[serde(default = "default_max_concurrency")]
+    max_concurrency: usize,
+}
+
+/// Default for `Config::max_concurrency`: one in-flight update per available CPU
+fn default_max_concurrency() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// A concurrency of 0 would mean `buffer_unordered()` never polls any container, so every tick
+/// would stall forever; clamp it up to 1 instead of trusting the raw config value.
+fn clamp_max_concurrency(max_concurrency: usize) -> usize {
+    if max_concurrency == 0 {
+        warn!("max_concurrency of 0 in config is invalid, clamping to 1");
+        1
+    } else {
+        max_concurrency
+    }
 }
 
 /// The different methods of connecting to the Docker daemon
-///
-/// Currently, support for only these 3 is planned. TLS/SSL may prove to be problematic to support,
-/// in which case I will most likely drop it.
 #[derive(Deserialize, Debug)]
 pub enum DockerConnectMode {
     #[serde(rename(deserialize = "unix_socket"))]
     UnixSocket,
     #[serde(rename(deserialize = "http"))]
-    Http,
+    Http { address: String },
     #[serde(rename(deserialize = "ssl"))]
-    SSL,
+    SSL {
+        address: String,
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        ca_path: PathBuf,
+    },
 }
 
 impl Default for DockerConnectMode {
@@ -43,11 +71,65 @@ impl Default for DockerConnectMode {
     }
 }
 
+impl DockerConnectMode {
+    /// Construct a bollard `Docker` client for this connection mode
+    ///
+    /// `UnixSocket` goes through bollard's socket defaults, `Http` dials the configured address
+    /// over plain TCP, and `SSL` does the same but mutually authenticates with the configured
+    /// client cert/key and CA. Missing TLS material is reported up front rather than silently
+    /// falling back to the socket.
+    pub fn connect(&self) -> Result<Docker, Error> {
+        match self {
+            DockerConnectMode::UnixSocket => {
+                Docker::connect_with_socket_defaults().map_err(|e| Error::DockerConnect {
+                    source: Box::new(e),
+                })
+            }
+            DockerConnectMode::Http { address } => {
+                Docker::connect_with_http(address, 120, bollard::API_DEFAULT_VERSION).map_err(|e| {
+                    Error::DockerConnect {
+                        source: Box::new(e),
+                    }
+                })
+            }
+            DockerConnectMode::SSL {
+                address,
+                cert_path,
+                key_path,
+                ca_path,
+            } => {
+                for (label, path) in [
+                    ("client certificate", cert_path),
+                    ("client key", key_path),
+                    ("CA certificate", ca_path),
+                ] {
+                    if !path.exists() {
+                        return Err(Error::DockerConnect {
+                            source: format!("missing TLS {} at {}", label, path.display()).into(),
+                        });
+                    }
+                }
+                Docker::connect_with_ssl(
+                    address,
+                    key_path,
+                    cert_path,
+                    ca_path,
+                    120,
+                    bollard::API_DEFAULT_VERSION,
+                )
+                .map_err(|e| Error::DockerConnect {
+                    source: Box::new(e),
+                })
+            }
+        }
+    }
+}
+
 impl Config {
     /// Get the global config for the current program instance
     ///
     /// This will read from disk, args and environment so unfortunately the contents are messy.
-    pub fn get() -> Result<Config, Box<dyn std::error::Error>> {
+    pub fn get() -> Result<Config, Error> {
         // Get args and consume the first one to remove the program invocation string
         let mut args = env::args();
         args.next();
@@ -64,11 +146,21 @@ impl Config {
             DEFAULT_CONFIG_PATH.to_owned()
         });
         if !config_path.exists() {
-            return Err(format!("Config file {} not found!", config_path.display()).into());
+            return Err(Error::Config {
+                path: config_path,
+                source: "config file not found".into(),
+            });
         }
 
         // Deserialise
-        let mut ret: Config = serde_yaml::from_str(&std::fs::read_to_string(&config_path)?)?;
+        let raw = std::fs::read_to_string(&config_path).map_err(|e| Error::Config {
+            path: config_path.clone(),
+            source: Box::new(e),
+        })?;
+        let mut ret: Config = serde_yaml::from_str(&raw).map_err(|e| Error::Config {
+            path: config_path.clone(),
+            source: Box::new(e),
+        })?;
 
         // TODO: Should the environment override the config file?
         // Get the API key from the environnment if it's not in the config
@@ -79,9 +171,11 @@ impl Config {
                     ret.steam_api_key = k;
                 }
                 Err(_) => {
-                    return Err(
-                        "Steam API key not found in configuration file or environment".into(),
-                    );
+                    return Err(Error::Config {
+                        path: config_path,
+                        source: "Steam API key not found in configuration file or environment"
+                            .into(),
+                    });
                 }
             }
         }
@@ -102,6 +196,8 @@ impl Config {
             _ => {}
         }
 
+        ret.max_concurrency = clamp_max_concurrency(ret.max_concurrency);
+
         Ok(ret)
     }
 
@@ -113,6 +209,7 @@ impl Config {
         Duration,
         PathBuf,
         DockerConnectMode,
+        usize,
     ) {
         (
             self.containers,
@@ -120,6 +217,36 @@ impl Config {
             self.check_interval,
             self.state_directory,
             self.connect_mode,
+            self.max_concurrency,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_max_concurrency_raises_zero_to_one() {
+        assert_eq!(clamp_max_concurrency(0), 1);
+    }
+
+    #[test]
+    fn clamp_max_concurrency_leaves_nonzero_values_alone() {
+        assert_eq!(clamp_max_concurrency(1), 1);
+        assert_eq!(clamp_max_concurrency(8), 8);
+    }
+
+    #[test]
+    fn connect_reports_missing_tls_material_instead_of_connecting() {
+        let mode = DockerConnectMode::SSL {
+            address: "tcp://localhost:2376".to_owned(),
+            cert_path: PathBuf::from("/nonexistent/cert.pem"),
+            key_path: PathBuf::from("/nonexistent/key.pem"),
+            ca_path: PathBuf::from("/nonexistent/ca.pem"),
+        };
+
+        let result = mode.connect();
+        assert!(matches!(result, Err(Error::DockerConnect { .. })));
+    }
+}
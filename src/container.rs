@@ -1,10 +1,51 @@
+use crate::error::Error;
 use crate::steam::{get_game_version, SteamVersion};
+use bollard::auth::DockerCredentials;
+use bollard::container::{
+    Config as ContainerConfig, CreateContainerOptions, RemoveContainerOptions,
+    RenameContainerOptions, StopContainerOptions,
+};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::image::{BuildImageOptions, CreateImageOptions};
 use bollard::Docker;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Run an update check across all containers concurrently
+///
+/// Drives each container's [`Container::update`] through a bounded pool of at most
+/// `max_concurrency` tasks in flight, so one slow build or pull doesn't block the update check
+/// for every other container until the next tick. Each container's state is persisted to
+/// `state_dir` as soon as its own update completes, rather than waiting for the whole batch.
+pub async fn update_all(
+    containers: Vec<Container>,
+    api_key: &str,
+    docker_client: &Docker,
+    state_dir: &PathBuf,
+    max_concurrency: usize,
+) -> Vec<Container> {
+    stream::iter(containers.into_iter().map(|mut container| async move {
+        if let Err(e) = container.update(api_key, docker_client).await {
+            error!("FAILED updating container {}: {}", container.name, e);
+        }
+        if let Err(e) = container.save_state(state_dir) {
+            error!(
+                "FAILED saving state for container {}: {}",
+                container.name, e
+            );
+        }
+        container
+    }))
+    .buffer_unordered(max_concurrency)
+    .collect()
+    .await
+}
+
+#[derive(Deserialize, Debug)]
 pub struct Container {
     pub name: String,
     pub appid: u64,
@@ -15,7 +56,38 @@ pub struct Container {
     options: BTreeMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// On-disk schema version for [`SavedState`], bumped whenever its shape changes
+const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// The subset of a [`Container`]'s state that's actually worth persisting to disk
+///
+/// Deliberately decoupled from `Container` itself so that adding fields to the config-facing
+/// struct never breaks existing state files, and so the schema can be migrated forward
+/// explicitly instead of by accident. Files written before this type existed have no
+/// `schema_version` field, which `#[serde(default)]` reads as `0`; [`SavedState::migrate`] brings
+/// those up to date.
+#[derive(Serialize, Deserialize, Debug)]
+struct SavedState {
+    #[serde(default)]
+    schema_version: u32,
+    current_version: SteamVersion,
+}
+
+impl SavedState {
+    /// Upgrade an on-disk schema to the current one
+    ///
+    /// Schema 0 predates this type and was a dump of the whole `Container`; since the
+    /// `current_version` field name hasn't changed, the only thing needed is to stamp the
+    /// current schema version on it.
+    fn migrate(mut self) -> Self {
+        if self.schema_version < CURRENT_SCHEMA_VERSION {
+            self.schema_version = CURRENT_SCHEMA_VERSION;
+        }
+        self
+    }
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
 pub enum UpdateAction {
     #[serde(rename = "build")]
     DockerBuild { context_path: PathBuf },
@@ -37,7 +109,12 @@ impl Container {
     /// version from that and then check the version is up to date.
     /// If there isn't, it will assume the existing container is up to date, get the version
     /// number from steam and stop.
-    pub async fn init(&mut self, key: &str, docker_client: &Docker, state_dir: &PathBuf) {
+    pub async fn init(
+        &mut self,
+        key: &str,
+        docker_client: &Docker,
+        state_dir: &PathBuf,
+    ) -> Result<(), Error> {
         debug!(
             "Initialising container {} (appid {})",
             self.name, self.appid
@@ -45,52 +122,78 @@ impl Container {
 
         let save_path = self.get_save_path(state_dir);
 
-        // Load in the saved version
+        // Load in the saved version. A save file that can't be read or parsed is treated as if
+        // it wasn't there at all: we log why and fall back to re-querying Steam, so a corrupt
+        // state file self-heals on the next run instead of taking the whole daemon down.
         if save_path.exists() {
             info!(
                 "Saved state for {} found at {}",
                 self.name,
                 save_path.display()
             );
-            let content = match std::fs::read_to_string(&save_path) {
-                Ok(c) => c,
-                Err(e) => panic!("FAILED to read state file {}: {}", &save_path.display(), e),
-            };
-            let saved_version: Self = match serde_json::from_str(&content) {
-                Ok(s) => s,
-                Err(e) => panic!(
-                    "FAILED to deserialise state from file {}: {}",
-                    &save_path.display(),
-                    e
-                ),
-            };
-            self.current_version = saved_version.current_version;
+            match self.load_state(&save_path) {
+                Ok(current_version) => {
+                    self.current_version = current_version;
 
-            // Check the game is up-to-date now that we've initialised it
-            debug!("Running initial update check for {}", self.name);
-            self.update(key, docker_client).await;
-        } else {
-            match get_game_version(key, self.appid).await {
-                Ok(v) => {
-                    info!(
-                        "Initialised container {} (appid {}): version {} found",
-                        self.name, self.appid, v
+                    // Check the game is up-to-date now that we've initialised it
+                    debug!("Running initial update check for {}", self.name);
+                    self.update(key, docker_client).await?;
+                }
+                Err(e) => {
+                    error!(
+                        "Ignoring unreadable state for {}, will re-query Steam: {}",
+                        self.name, e
                     );
-                    self.current_version = v;
+                    self.query_initial_version(key).await?;
                 }
-                Err(e) => error!(
-                    "FAILED to initialise container {} (appid {}): {}",
-                    self.name, self.appid, e
-                ),
             }
+        } else {
+            self.query_initial_version(key).await?;
         }
+
+        Ok(())
+    }
+
+    /// Ask Steam for the current version and adopt it as our baseline
+    ///
+    /// Used for containers with no saved state yet, or whose saved state couldn't be trusted.
+    async fn query_initial_version(&mut self, key: &str) -> Result<(), Error> {
+        let v = get_game_version(key, self.appid)
+            .await
+            .map_err(|e| Error::SteamApi {
+                appid: self.appid,
+                source: Box::new(e),
+            })?;
+        info!(
+            "Initialised container {} (appid {}): version {} found",
+            self.name, self.appid, v
+        );
+        self.current_version = v;
+        Ok(())
+    }
+
+    /// Read and deserialise the saved `current_version` from a state file
+    fn load_state(&self, save_path: &PathBuf) -> Result<SteamVersion, Error> {
+        let content = std::fs::read_to_string(save_path).map_err(|e| Error::StateRead {
+            container: self.name.clone(),
+            path: save_path.clone(),
+            source: e,
+        })?;
+        let saved: SavedState = serde_json::from_str(&content)
+            .map_err(|e| Error::StateDeserialize {
+                container: self.name.clone(),
+                path: save_path.clone(),
+                source: e,
+            })?
+            .migrate();
+        Ok(saved.current_version)
     }
 
     /// Check for updates and carry them out on a container
     ///
     /// Checks for version changes via steam, and if the versions don't match, runs the relevant
     /// update handler for that container (restart, pull etc.)
-    pub async fn update(&mut self, api_key: &str, docker_client: &Docker) {
+    pub async fn update(&mut self, api_key: &str, docker_client: &Docker) -> Result<(), Error> {
         // Get the version integer from steam
         debug!("Checking version of {}", self.name);
         let new_version = match get_game_version(&api_key, self.appid).await {
@@ -102,11 +205,17 @@ impl Container {
                 v
             }
             Err(e) => {
+                // Transient Steam API failures are retried next tick rather than treated as
+                // fatal, but still get wrapped so the failure is logged with the same structure
+                // as every other error path.
                 error!(
-                    "FAILED to check version of container {} (appid {}): {}",
-                    self.name, self.appid, e
+                    "{}",
+                    Error::SteamApi {
+                        appid: self.appid,
+                        source: Box::new(e),
+                    }
                 );
-                return;
+                return Ok(());
             }
         };
 
@@ -116,89 +225,591 @@ impl Container {
                 "{} is UP-TO-DATE at version {}",
                 self.name, self.current_version
             );
-            return;
+            return Ok(());
         }
 
         // Check the container is running, if not, warn and skip
-        let container_running =
-            match docker_client.inspect_container(&self.name, None).await {
-                Ok(r) => {
-                    if let Some(state) = r.state {
-                        state.running == Some(true)
-                    } else {
-                        error!(
-                            "FAILED inspecting container {}: no state returned by docker",
-                            self.name
-                        );
-                        return;
-                    }
-                }
-                Err(e) => {
-                    error!("FAILED inspecting container {}: {}", self.name, e);
-                    return;
-                }
-            };
+        let inspected = docker_client
+            .inspect_container(&self.name, None)
+            .await
+            .map_err(|e| Error::DockerInspect {
+                container: self.name.clone(),
+                source: e,
+            })?;
+        let container_running = match inspected.state {
+            Some(state) => state.running == Some(true),
+            None => {
+                error!(
+                    "FAILED inspecting container {}: no state returned by docker",
+                    self.name
+                );
+                return Ok(());
+            }
+        };
         if !container_running {
             warn!(
                 "Container {} not running, skipping update action",
                 self.name
             );
-            return;
+            return Ok(());
         }
 
         // Otherwise, start our update action and update the version tag if the update completes
         // successfully
-        match self.action {
-            UpdateAction::DockerRestart => {
-                if let Ok(_) = self.restart(docker_client).await {
-                    self.current_version = new_version;
+        match &self.action {
+            UpdateAction::DockerRestart => match self.restart(docker_client).await {
+                Ok(_) => self.current_version = new_version,
+                Err(e) => error!("FAILED update action for {}: {}", self.name, e),
+            },
+            UpdateAction::DockerPull { image, tag } => {
+                let (image, tag) = (image.clone(), tag.clone());
+                match self.pull(docker_client, &image, &tag).await {
+                    Ok(_) => self.current_version = new_version,
+                    Err(e) => error!("FAILED update action for {}: {}", self.name, e),
+                }
+            }
+            UpdateAction::DockerBuild { context_path } => {
+                let context_path = context_path.clone();
+                match self.build(docker_client, &context_path).await {
+                    Ok(_) => self.current_version = new_version,
+                    Err(e) => error!("FAILED update action for {}: {}", self.name, e),
+                }
+            }
+            UpdateAction::Custom { chdir, command } => {
+                let (chdir, command) = (chdir.clone(), command.clone());
+                match self.exec(docker_client, &chdir, &command).await {
+                    Ok(_) => self.current_version = new_version,
+                    Err(e) => error!("FAILED update action for {}: {}", self.name, e),
                 }
             }
-            _ => todo!(),
         }
+
+        Ok(())
     }
 
     /// Restart a container
     ///
     /// For containers which have an update command in their entrypoint scripts. Many cimages from
     /// docker hub follow this pattern.
-    pub async fn restart(&self, docker_client: &Docker) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn restart(&self, docker_client: &Docker) -> Result<(), Error> {
         debug!("Restarting container {}", self.name);
-        match docker_client.restart_container(&self.name, None).await {
+        docker_client
+            .restart_container(&self.name, None)
+            .await
+            .map_err(|e| Error::DockerAction {
+                container: self.name.clone(),
+                action: "restart",
+                source: Box::new(e),
+            })?;
+        info!("Container {} successfully updated via restart", self.name);
+        Ok(())
+    }
+
+    /// Pull a new image and recreate the container from it
+    ///
+    /// Fetches `image:tag` from its registry (optionally authenticating with credentials from
+    /// `options`), captures the running container's configuration, then recreates it from the
+    /// new image so it picks up the update. The old container is only renamed out of the way,
+    /// never removed, until the replacement is confirmed running; if anything goes wrong it is
+    /// restored under its original name instead of leaving the game with no container at all.
+    pub async fn pull(&self, docker_client: &Docker, image: &str, tag: &str) -> Result<(), Error> {
+        debug!(
+            "Pulling image {}:{} for container {}",
+            image, tag, self.name
+        );
+
+        let auth = self.registry_auth();
+        let create_image_options = CreateImageOptions {
+            from_image: image,
+            tag,
+            ..Default::default()
+        };
+        let mut pull_stream = docker_client.create_image(Some(create_image_options), None, auth);
+        while let Some(progress) = pull_stream.next().await {
+            let info = progress.map_err(|e| Error::DockerAction {
+                container: self.name.clone(),
+                action: "pull",
+                source: Box::new(e),
+            })?;
+            debug!("Pulling {}: {:?}", self.name, info);
+        }
+
+        let new_image = format!("{}:{}", image, tag);
+        self.recreate_from_image(docker_client, &new_image).await?;
+
+        info!(
+            "Container {} successfully updated via image pull ({}:{})",
+            self.name, image, tag
+        );
+        Ok(())
+    }
+
+    /// Recreate the container from `new_image`, without ever leaving it entirely gone
+    ///
+    /// Captures the running container's env/cmd/host config, stops it and renames it out of the
+    /// way (never removes it up front), then creates and starts the replacement under the
+    /// original name. The backup is only removed once the replacement is confirmed running; on
+    /// any failure along the way, the half-created replacement is torn down and the backup is
+    /// renamed back and restarted, so a bad image/config never costs us the container entirely.
+    async fn recreate_from_image(
+        &self,
+        docker_client: &Docker,
+        new_image: &str,
+    ) -> Result<(), Error> {
+        let to_action_err = |e: bollard::errors::Error| Error::DockerAction {
+            container: self.name.clone(),
+            action: "recreate",
+            source: Box::new(e),
+        };
+
+        let old = docker_client
+            .inspect_container(&self.name, None)
+            .await
+            .map_err(to_action_err)?;
+        let host_config = old.host_config;
+        let cmd = old.config.as_ref().and_then(|c| c.cmd.clone());
+        let env = old.config.as_ref().and_then(|c| c.env.clone());
+
+        let backup_name = format!("{}_old", self.name);
+        docker_client
+            .stop_container(&self.name, None::<StopContainerOptions>)
+            .await
+            .map_err(to_action_err)?;
+        if let Err(e) = docker_client
+            .rename_container(
+                &self.name,
+                RenameContainerOptions {
+                    name: backup_name.clone(),
+                },
+            )
+            .await
+        {
+            // We've already stopped the container and have nothing new to replace it with yet,
+            // so leaving it stopped here would silently drop the game rather than just failing
+            // the update; best-effort restart it under its original name before giving up.
+            error!(
+                "FAILED to rename {} out of the way, restarting it in place: {}",
+                self.name, e
+            );
+            if let Err(se) = docker_client
+                .start_container::<String>(&self.name, None)
+                .await
+            {
+                error!(
+                    "FAILED to restart container {} after aborted rename: {}",
+                    self.name, se
+                );
+            }
+            return Err(to_action_err(e));
+        }
+
+        let container_config = ContainerConfig {
+            image: Some(new_image),
+            env: env.as_ref().map(|e| e.iter().map(String::as_str).collect()),
+            cmd: cmd.as_ref().map(|c| c.iter().map(String::as_str).collect()),
+            host_config,
+            ..Default::default()
+        };
+
+        let recreated: Result<(), Error> = async {
+            docker_client
+                .create_container(
+                    Some(CreateContainerOptions {
+                        name: self.name.as_str(),
+                        platform: None,
+                    }),
+                    container_config,
+                )
+                .await
+                .map_err(to_action_err)?;
+            docker_client
+                .start_container::<String>(&self.name, None)
+                .await
+                .map_err(to_action_err)?;
+            let running = docker_client
+                .inspect_container(&self.name, None)
+                .await
+                .map_err(to_action_err)?
+                .state
+                .and_then(|s| s.running)
+                .unwrap_or(false);
+            if running {
+                Ok(())
+            } else {
+                Err(Error::DockerAction {
+                    container: self.name.clone(),
+                    action: "recreate",
+                    source: format!("container {} did not come back up running", self.name).into(),
+                })
+            }
+        }
+        .await;
+
+        match recreated {
+            Ok(()) => {
+                if let Err(e) = docker_client
+                    .remove_container(&backup_name, None::<RemoveContainerOptions>)
+                    .await
+                {
+                    warn!(
+                        "Updated container {} but failed to clean up backup {}: {}",
+                        self.name, backup_name, e
+                    );
+                }
+                Ok(())
+            }
             Err(e) => {
-                error!("FAILED to restart container {}: {}", self.name, &e);
-                Err(Box::new(e))
+                error!(
+                    "FAILED to bring up container {} on image {}, rolling back: {}",
+                    self.name, new_image, e
+                );
+                let _ = docker_client
+                    .remove_container(
+                        &self.name,
+                        Some(RemoveContainerOptions {
+                            force: true,
+                            ..Default::default()
+                        }),
+                    )
+                    .await;
+                match docker_client
+                    .rename_container(
+                        &backup_name,
+                        RenameContainerOptions {
+                            name: self.name.clone(),
+                        },
+                    )
+                    .await
+                {
+                    Ok(_) => {
+                        if let Err(se) = docker_client
+                            .start_container::<String>(&self.name, None)
+                            .await
+                        {
+                            error!("FAILED to restart restored container {}: {}", self.name, se);
+                        }
+                    }
+                    Err(re) => error!(
+                        "FAILED to restore container {} from backup {}: {}",
+                        self.name, backup_name, re
+                    ),
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Build a new image from `context_path` and recreate the container from it
+    ///
+    /// Tars up the build context, feeds it to the Docker daemon via `build_image`, streams the
+    /// build log at debug level, then recreates the container from the freshly built image tag
+    /// via the same safe, backup-then-confirm path as [`Container::pull`].
+    pub async fn build(&self, docker_client: &Docker, context_path: &PathBuf) -> Result<(), Error> {
+        let image_tag = self
+            .options
+            .get("tag")
+            .cloned()
+            .unwrap_or_else(|| format!("{}:latest", self.name));
+        let dockerfile = self
+            .options
+            .get("dockerfile")
+            .cloned()
+            .unwrap_or_else(|| "Dockerfile".to_owned());
+        let buildargs: HashMap<String, String> = self
+            .options
+            .iter()
+            .filter_map(|(k, v)| {
+                k.strip_prefix("buildarg_")
+                    .map(|arg| (arg.to_owned(), v.clone()))
+            })
+            .collect();
+
+        debug!(
+            "Building image {} for container {} from context {}",
+            image_tag,
+            self.name,
+            context_path.display()
+        );
+        let context_tar = self.tar_context(context_path)?;
+
+        let build_options = BuildImageOptions {
+            t: image_tag.clone(),
+            dockerfile: dockerfile.clone(),
+            buildargs,
+            ..Default::default()
+        };
+        let mut build_stream =
+            docker_client.build_image(build_options, None, Some(context_tar.into()));
+        while let Some(progress) = build_stream.next().await {
+            let info = progress.map_err(|e| Error::DockerAction {
+                container: self.name.clone(),
+                action: "build",
+                source: Box::new(e),
+            })?;
+            debug!("Building {}: {:?}", self.name, info);
+        }
+
+        self.recreate_from_image(docker_client, &image_tag).await?;
+
+        info!(
+            "Container {} successfully updated via image build ({})",
+            self.name, image_tag
+        );
+        Ok(())
+    }
+
+    /// Tar and gzip a build context directory into memory for `build_image`
+    fn tar_context(&self, context_path: &PathBuf) -> Result<Vec<u8>, Error> {
+        let tar_gz = || -> std::io::Result<Vec<u8>> {
+            let enc = GzEncoder::new(Vec::new(), Compression::default());
+            let mut tar = tar::Builder::new(enc);
+            tar.append_dir_all(".", context_path)?;
+            tar.into_inner()?.finish()
+        };
+        tar_gz().map_err(|e| Error::DockerAction {
+            container: self.name.clone(),
+            action: "build: tar context",
+            source: Box::new(e),
+        })
+    }
+
+    /// Run a custom update command inside the running container
+    ///
+    /// Splits `command` as a shell would, execs it in `chdir`, streams its combined
+    /// stdout/stderr to the log, then inspects the exec's real exit code: the version is only
+    /// advanced on a clean (0) exit, so a failing command is retried on the next tick.
+    pub async fn exec(
+        &self,
+        docker_client: &Docker,
+        chdir: &PathBuf,
+        command: &str,
+    ) -> Result<(), Error> {
+        debug!(
+            "Running custom update command for {} in {}: {}",
+            self.name,
+            chdir.display(),
+            command
+        );
+        let to_action_err =
+            |source: Box<dyn std::error::Error + Send + Sync>| Error::DockerAction {
+                container: self.name.clone(),
+                action: "custom",
+                source,
+            };
+
+        let cmd = shell_words::split(command).map_err(|e| to_action_err(Box::new(e)))?;
+
+        let exec = docker_client
+            .create_exec(
+                &self.name,
+                CreateExecOptions {
+                    cmd: Some(cmd),
+                    working_dir: Some(chdir.to_string_lossy().into_owned()),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(|e| to_action_err(Box::new(e)))?;
+
+        if let StartExecResults::Attached { mut output, .. } = docker_client
+            .start_exec(&exec.id, None)
+            .await
+            .map_err(|e| to_action_err(Box::new(e)))?
+        {
+            while let Some(chunk) = output.next().await {
+                match chunk {
+                    Ok(log) => debug!("{} update command: {}", self.name, log),
+                    Err(e) => error!(
+                        "FAILED reading update command output for {}: {}",
+                        self.name, e
+                    ),
+                }
             }
-            _ => {
-                info!("Container {} successfully updated via restart", self.name);
+        }
+
+        let exit_code = docker_client
+            .inspect_exec(&exec.id)
+            .await
+            .map_err(|e| to_action_err(Box::new(e)))?
+            .exit_code;
+        match exit_code {
+            Some(0) => {
+                info!("Container {} update command exited successfully", self.name);
                 Ok(())
             }
+            Some(code) => {
+                error!("Update command for {} exited with code {}", self.name, code);
+                Err(to_action_err(
+                    format!("update command for {} exited with code {}", self.name, code).into(),
+                ))
+            }
+            None => {
+                error!(
+                    "Update command for {} did not report an exit code",
+                    self.name
+                );
+                Err(to_action_err(
+                    format!("update command for {} did not finish", self.name).into(),
+                ))
+            }
+        }
+    }
+
+    /// Build registry credentials for image pulls from the container's `options` map
+    ///
+    /// Looks for `username`, `password` and `serveraddress` entries; returns `None` if none of
+    /// them are set, so public images keep working without any config.
+    fn registry_auth(&self) -> Option<DockerCredentials> {
+        let username = self.options.get("username").cloned();
+        let password = self.options.get("password").cloned();
+        let serveraddress = self.options.get("serveraddress").cloned();
+        if username.is_none() && password.is_none() && serveraddress.is_none() {
+            return None;
         }
+        Some(DockerCredentials {
+            username,
+            password,
+            serveraddress,
+            ..Default::default()
+        })
     }
 
     /// Save the state of the container to disk
     ///
     /// Creates/updates a {container name}.json file with a simple serialisation of the container
     /// object in it.
-    pub fn save_state(&self, dir: &PathBuf) {
+    pub fn save_state(&self, dir: &PathBuf) -> Result<(), Error> {
         // Save the current state to the save file directory, currently only used to save version
-        // between restarts
+        // between restarts. Written atomically via a `.tmp` sibling + rename so a crash mid-write
+        // can never leave a truncated, unparseable state file behind.
         debug!("Saving container {}'s state to disk", self.name);
-        let serial = match serde_json::to_string(&self) {
-            Ok(s) => s,
-            Err(e) => panic!("FAILED to serialise container {}: {}", self.name, e),
+        let state = SavedState {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            current_version: self.current_version.clone(),
         };
+        let serial = serde_json::to_string(&state).map_err(|e| Error::StateSerialize {
+            container: self.name.clone(),
+            source: e,
+        })?;
+
         let save_path = self.get_save_path(dir);
-        match std::fs::write(&save_path, serial) {
-            Err(e) => panic!("FAILED saving container {} state to disk: {}", self.name, e),
-            _ => (),
-        }
+        let tmp_path = save_path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serial).map_err(|e| Error::StateWrite {
+            container: self.name.clone(),
+            path: tmp_path.clone(),
+            source: e,
+        })?;
+        std::fs::rename(&tmp_path, &save_path).map_err(|e| Error::StateWrite {
+            container: self.name.clone(),
+            path: save_path,
+            source: e,
+        })
     }
 
     /// Helper method to create the path string for the save file for this container
     fn get_save_path(&self, dir: &PathBuf) -> PathBuf {
-        [dir.to_str().unwrap(), &format!("{}.json", self.name)]
-            .iter()
-            .collect()
+        [
+            dir.to_string_lossy().as_ref(),
+            &format!("{}.json", self.name),
+        ]
+        .iter()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_container(name: &str) -> Container {
+        Container {
+            name: name.to_owned(),
+            appid: 1,
+            current_version: SteamVersion::default(),
+            action: UpdateAction::DockerRestart,
+            options: BTreeMap::new(),
+        }
+    }
+
+    /// Give each test its own scratch directory under the system temp dir, named after the test
+    /// itself so parallel test runs don't clobber each other's save files.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("heat_exchanger_test_{}", name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn migrate_stamps_legacy_schema_0_files() {
+        // Files written before SavedState existed have no schema_version field at all;
+        // `#[serde(default)]` reads that as 0.
+        let saved: SavedState = serde_json::from_str(r#"{"current_version":"1.2.3"}"#)
+            .expect("legacy state without schema_version should still deserialise");
+        assert_eq!(saved.schema_version, 0);
+
+        let migrated = saved.migrate();
+        assert_eq!(migrated.schema_version, CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn migrate_leaves_current_schema_untouched() {
+        let saved = SavedState {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            current_version: SteamVersion::default(),
+        }
+        .migrate();
+        assert_eq!(saved.schema_version, CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn load_state_reports_corrupt_file_as_deserialize_error() {
+        let container = test_container("load_state_corrupt");
+        let dir = test_dir("load_state_corrupt");
+        let save_path = container.get_save_path(&dir);
+        std::fs::write(&save_path, "not valid json").unwrap();
+
+        let result = container.load_state(&save_path);
+        assert!(matches!(result, Err(Error::StateDeserialize { .. })));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn load_state_missing_file_is_an_error_not_a_panic() {
+        let container = test_container("load_state_missing");
+        let dir = test_dir("load_state_missing");
+        let save_path = container.get_save_path(&dir);
+        std::fs::remove_file(&save_path).ok();
+
+        let result = container.load_state(&save_path);
+        assert!(matches!(result, Err(Error::StateRead { .. })));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn save_state_writes_atomically_and_round_trips() {
+        let container = test_container("save_state_roundtrip");
+        let dir = test_dir("save_state_roundtrip");
+
+        container
+            .save_state(&dir)
+            .expect("save_state should succeed");
+
+        let save_path = container.get_save_path(&dir);
+        assert!(save_path.exists(), "final state file should exist");
+        assert!(
+            !save_path.with_extension("json.tmp").exists(),
+            "the .tmp sibling should be gone once the rename lands"
+        );
+
+        let loaded = container
+            .load_state(&save_path)
+            .expect("state just written should load back cleanly");
+        assert_eq!(loaded, container.current_version);
+
+        std::fs::remove_dir_all(&dir).ok();
     }
 }
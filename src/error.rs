@@ -0,0 +1,81 @@
+use miette::Diagnostic;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Crate-wide error type
+///
+/// Every fallible operation that would previously `panic!` or erase its cause behind
+/// `Box<dyn Error>` returns one of these instead, so a single bad state file or container can be
+/// logged and skipped rather than taking the whole daemon down.
+#[derive(Error, Diagnostic, Debug)]
+pub enum Error {
+    #[error("failed to read state file for container {container} at {path}")]
+    StateRead {
+        container: String,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to deserialise state for container {container} from {path}")]
+    StateDeserialize {
+        container: String,
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("failed to serialise state for container {container}")]
+    StateSerialize {
+        container: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("failed to write state file for container {container} at {path}")]
+    StateWrite {
+        container: String,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to inspect docker container {container}")]
+    DockerInspect {
+        container: String,
+        #[source]
+        source: bollard::errors::Error,
+    },
+
+    #[error("failed to query steam for appid {appid}")]
+    SteamApi {
+        appid: u64,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("failed to connect to the docker daemon")]
+    DockerConnect {
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("update action '{action}' failed for container {container}")]
+    DockerAction {
+        container: String,
+        action: &'static str,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("failed to load configuration from {path}")]
+    #[diagnostic(
+        code(heat_exchanger::config),
+        help("check the file exists, is readable, and is valid YAML matching the documented config schema")
+    )]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}